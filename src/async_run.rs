@@ -0,0 +1,85 @@
+//! Opt-in, non-blocking event loop (the `async` cargo feature) built on
+//! tokio. Unlike the default `stdin().lock().bytes()` loop, this one can
+//! react to terminal resizes (SIGWINCH) while otherwise idle: key reading
+//! runs on its own blocking thread (the same `Read::bytes()` loop the sync
+//! build uses, so escape sequences and incremental search decode exactly
+//! as they do there) while the async task waits on either that thread or
+//! a SIGWINCH stream.
+
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+
+use nix::libc::STDIN_FILENO;
+use nix::sys::termios;
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::errors::*;
+use super::{draw_rows, dump_file, enable_raw_mode, is_interactive, process_key, read_key, read_window_size,
+            refresh_screen, term_config};
+
+pub fn run() -> Result<()> {
+    let mut rt = tokio::runtime::Runtime::new().chain_err(|| "Unable to start the tokio runtime")?;
+    rt.block_on(run_async())
+}
+
+async fn run_async() -> Result<()> {
+    let path = std::env::args().nth(1);
+
+    if !is_interactive() {
+        return match path {
+            Some(path) => dump_file(&path),
+            None => Err("stdin/stdout is not a terminal; pass a file to print, or run interactively".into()),
+        };
+    }
+
+    let mut config = term_config().chain_err(|| "Unable to initialize terminal config")?;
+    if let Some(path) = path {
+        config.rows = crate::buffer::open(path)?;
+    }
+    let config = Arc::new(Mutex::new(config));
+
+    let result = async {
+        enable_raw_mode()?;
+        draw_rows(&config.lock().unwrap())?;
+
+        let mut resize = signal(SignalKind::window_change())
+            .chain_err(|| "Unable to watch for SIGWINCH")?;
+
+        let input_config = config.clone();
+        let input_task = tokio::task::spawn_blocking(move || -> Result<()> {
+            let stdin = std::io::stdin();
+            let mut bytes = stdin.lock().bytes();
+            while let Some(key) = read_key(&mut bytes) {
+                let mut conf = input_config.lock().unwrap();
+                if !process_key(key, &mut conf, &mut bytes)? {
+                    break;
+                }
+                draw_rows(&conf)?;
+            }
+            Ok(())
+        });
+        tokio::pin!(input_task);
+
+        loop {
+            tokio::select! {
+                res = &mut input_task => {
+                    res.chain_err(|| "the input thread panicked")??;
+                    break;
+                }
+                _ = resize.recv() => {
+                    let (total_rows, screencols) = read_window_size()?;
+                    let mut conf = config.lock().unwrap();
+                    conf.screenrows = total_rows - 1;
+                    conf.screencols = screencols;
+                    draw_rows(&conf)?;
+                }
+            }
+        }
+        Ok(())
+    }.await;
+
+    let conf = config.lock().unwrap();
+    refresh_screen(&conf)?;
+    termios::tcsetattr(STDIN_FILENO, termios::SetArg::TCSAFLUSH, &conf.orig)?;
+    result
+}
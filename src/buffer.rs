@@ -0,0 +1,71 @@
+//! A file-backed text buffer: each line is kept both as the raw characters
+//! read from disk and as a rendered form with tabs expanded, which is what
+//! actually gets drawn to the screen.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::errors::*;
+
+const TAB_STOP: usize = 8;
+
+pub struct Row {
+    pub chars: String,
+    pub render: String,
+}
+
+impl Row {
+    pub fn new(chars: String) -> Row {
+        let render = render_tabs(&chars);
+        Row { chars: chars, render: render }
+    }
+}
+
+fn render_tabs(chars: &str) -> String {
+    let mut render = String::new();
+    for c in chars.chars() {
+        if c == '\t' {
+            render.push(' ');
+            while render.len() % TAB_STOP != 0 {
+                render.push(' ');
+            }
+        } else {
+            render.push(c);
+        }
+    }
+    render
+}
+
+/// Read `path` line-by-line into buffer rows, stripping the trailing
+/// newline from each line.
+pub fn open<P: AsRef<Path>>(path: P) -> Result<Vec<Row>> {
+    let path = path.as_ref();
+    let file = File::open(path).chain_err(|| format!("Unable to open {}", path.display()))?;
+    let mut rows = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.chain_err(|| format!("Unable to read {}", path.display()))?;
+        rows.push(Row::new(line));
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_tabs_passes_non_tab_text_through() {
+        assert_eq!(render_tabs("hello"), "hello");
+    }
+
+    #[test]
+    fn render_tabs_expands_to_the_next_tab_stop() {
+        assert_eq!(render_tabs("a\tb"), format!("a{}b", " ".repeat(7)));
+    }
+
+    #[test]
+    fn render_tabs_inserts_a_full_stop_when_already_aligned() {
+        assert_eq!(render_tabs("\t"), " ".repeat(8));
+    }
+}
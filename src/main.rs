@@ -3,22 +3,37 @@
 #[macro_use]
 extern crate error_chain;
 extern crate nix;
+#[cfg(feature = "async")]
+extern crate tokio;
 
 use std::io::Read;
 use std::io::Write;
 use nix::sys::termios;
 
-use nix::libc::STDIN_FILENO;
+use nix::libc::{STDIN_FILENO, STDOUT_FILENO};
 
 mod errors {
     // Create the Error, ErrorKind, ResultExt, and Result types
     error_chain!{
         foreign_links {
             Nix(::nix::Error);
+            Io(::std::io::Error);
         }
     }
 }
-use errors::*;
+use crate::errors::*;
+
+mod terminfo;
+use crate::terminfo::Terminfo;
+
+mod term_control;
+use crate::term_control::TermControl;
+
+mod buffer;
+use crate::buffer::Row;
+
+#[cfg(feature = "async")]
+mod async_run;
 
 /** constants **/
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
@@ -27,25 +42,49 @@ const ESCAPE: char = '\x1b';
 /** terminal**/
 struct EditorConfig {
     orig: termios::Termios,
-    rows: u16,
-    cols: u16,
+    screenrows: u16,
+    screencols: u16,
     cx: u16,
     cy: u16,
+    rowoff: u16,
+    coloff: u16,
+    rows: Vec<Row>,
+    ti: Terminfo,
+    status_msg: String,
+    /// Row/start/end of the span to render in reverse video, e.g. the
+    /// current search match.
+    hl: Option<(usize, usize, usize)>,
 }
 
 fn ctrl(c: char) -> u8 {
     (c as u8) & 0x1f
 }
 
+/// Whether both stdin and stdout are a real terminal. When output is
+/// piped or redirected, raw mode and escape sequences are meaningless.
+fn is_interactive() -> bool {
+    nix::unistd::isatty(STDIN_FILENO).unwrap_or(false) &&
+        nix::unistd::isatty(STDOUT_FILENO).unwrap_or(false)
+}
+
+/// Print `path` plainly, for the non-interactive fallback.
+fn dump_file(path: &str) -> Result<()> {
+    let mut file = std::fs::File::open(path).chain_err(|| format!("Unable to open {}", path))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).chain_err(|| format!("Unable to read {}", path))?;
+    print!("{}", contents);
+    Ok(())
+}
+
 fn enable_raw_mode() -> Result<()> {
     use termios::*;
 
     let mut raw = tcgetattr(STDIN_FILENO)?;
-    raw.c_iflag.remove(BRKINT | ICRNL | INPCK | IXON);
-    raw.c_oflag.remove(OPOST);
-    raw.c_cflag.insert(CS8);
-    raw.c_lflag.remove(ECHO | ICANON | IEXTEN | ISIG);
-    tcsetattr(STDIN_FILENO, TCSAFLUSH, &raw)?;
+    raw.input_flags.remove(InputFlags::BRKINT | InputFlags::ICRNL | InputFlags::INPCK | InputFlags::IXON);
+    raw.output_flags.remove(OutputFlags::OPOST);
+    raw.control_flags.insert(ControlFlags::CS8);
+    raw.local_flags.remove(LocalFlags::ECHO | LocalFlags::ICANON | LocalFlags::IEXTEN | LocalFlags::ISIG);
+    tcsetattr(STDIN_FILENO, SetArg::TCSAFLUSH, &raw)?;
 
     Ok(())
 }
@@ -65,18 +104,71 @@ fn read_window_size() -> Result<(u16, u16)> {
             return Ok((wc.ws_row as u16, wc.ws_col as u16));
         }
     }
-    Err("Unable to read terminal size".into())
+    cursor_position_window_size().chain_err(|| "Unable to read terminal size")
+}
+
+/// Fallback for terminals where `TIOCGWINSZ` reports zero dimensions
+/// (e.g. some serial/pty connections): push the cursor to the bottom
+/// right corner, ask for its position with a Device Status Report
+/// (`\x1b[6n`), and parse the `\x1b[<rows>;<cols>R` reply.
+fn cursor_position_window_size() -> Result<(u16, u16)> {
+    use termios::*;
+
+    let orig = tcgetattr(STDIN_FILENO)?;
+    let mut raw = orig.clone();
+    raw.local_flags.remove(LocalFlags::ICANON | LocalFlags::ECHO);
+    tcsetattr(STDIN_FILENO, SetArg::TCSAFLUSH, &raw)?;
+
+    let result = (|| -> Result<(u16, u16)> {
+        print!("\x1b[999C\x1b[999B\x1b[6n");
+        std::io::stdout().flush().chain_err(|| "Unable to flush stdout")?;
+
+        let mut reply = Vec::new();
+        let mut byte = [0u8; 1];
+        loop {
+            let n = nix::unistd::read(STDIN_FILENO, &mut byte)
+                .chain_err(|| "Unable to read the cursor position reply")?;
+            if n == 0 || byte[0] == b'R' {
+                break;
+            }
+            reply.push(byte[0]);
+        }
+
+        let reply = String::from_utf8_lossy(&reply);
+        let coords = reply.trim_start_matches("\x1b[");
+        let mut parts = coords.splitn(2, ';');
+        let rows = parts.next().and_then(|s| s.parse().ok());
+        let cols = parts.next().and_then(|s| s.parse().ok());
+        match (rows, cols) {
+            (Some(rows), Some(cols)) => Ok((rows, cols)),
+            _ => Err(format!("Malformed cursor position reply: {:?}", reply).into()),
+        }
+    })();
+
+    tcsetattr(STDIN_FILENO, SetArg::TCSAFLUSH, &orig)?;
+    result
 }
 
 fn term_config() -> Result<EditorConfig> {
     let orig = termios::tcgetattr(STDIN_FILENO)?;
-    let (rows, cols) = read_window_size()?;
+    let (total_rows, screencols) = read_window_size()?;
+    // Terminals with a terminfo entry in a format we don't parse (e.g. the
+    // 32-bit number layout used by xterm-256color/tmux-256color) shouldn't
+    // stop the editor from starting: fall back to the hardcoded CSI
+    // sequences that every TermControl method already knows.
+    let ti = Terminfo::from_env().unwrap_or_else(|_| Terminfo::empty());
     Ok(EditorConfig {
         orig: orig,
-        rows: rows,
-        cols: cols,
+        screenrows: total_rows - 1, // bottom row is reserved for the message bar
+        screencols: screencols,
         cx: 0,
         cy: 0,
+        rowoff: 0,
+        coloff: 0,
+        rows: Vec::new(),
+        ti: ti,
+        status_msg: String::new(),
+        hl: None,
     })
 }
 
@@ -88,22 +180,29 @@ enum Input {
     ArrowUp,
     ArrowDown,
     ArrowRight,
-    ArrowLeft
+    ArrowLeft,
+    PageUp,
+    PageDown,
+    Home,
+    End,
 }
 
-fn process_key(i: Input, conf: &mut EditorConfig) -> bool {
+fn process_key<I>(i: Input, conf: &mut EditorConfig, bytes: &mut I) -> Result<bool>
+    where I: Iterator<Item = std::io::Result<u8>>
+{
     use Input::*;
     match i {
-        Control(b) if b == ctrl('q') => return false,
-        Control(b) => print!("{}\r\n", b),
-        Char(c) => print!("{} ({})\r\n", c as u8, c), 
+        Control(b) if b == ctrl('q') => return Ok(false),
+        Control(b) if b == ctrl('f') => search(conf, bytes)?,
+        Control(_) => {}
+        Char(_) => {}
         ArrowUp => {
             if conf.cy > 0 {
                 conf.cy -= 1
             }
         }
         ArrowDown => {
-            if conf.cy < conf.rows - 1 {
+            if (conf.cy as usize) < conf.rows.len() {
                 conf.cy += 1
             }
         }
@@ -113,46 +212,209 @@ fn process_key(i: Input, conf: &mut EditorConfig) -> bool {
             }
         }
         ArrowRight => {
-            if conf.cx < conf.cols - 1 {
+            if conf.cx < conf.screencols - 1 {
                 conf.cx += 1
             }
         }
+        PageUp => {
+            conf.cy = conf.cy.saturating_sub(conf.screenrows);
+        }
+        PageDown => {
+            conf.cy = std::cmp::min(conf.cy + conf.screenrows, conf.rows.len() as u16);
+        }
+        Home => conf.cx = 0,
+        End => {
+            let line_len = conf.rows.get(conf.cy as usize).map_or(0, |row| row.render.len());
+            conf.cx = std::cmp::min(line_len as u16, conf.screencols - 1);
+        }
+    }
+    clamp_scroll(conf);
+    Ok(true)
+}
+
+/// After a cursor move, clamp `cy` to the buffer and adjust `rowoff`/
+/// `coloff` so the cursor stays within the visible window.
+fn clamp_scroll(conf: &mut EditorConfig) {
+    conf.cy = std::cmp::min(conf.cy, conf.rows.len() as u16);
+
+    if conf.cy < conf.rowoff {
+        conf.rowoff = conf.cy;
+    }
+    if conf.cy >= conf.rowoff + conf.screenrows {
+        conf.rowoff = conf.cy - conf.screenrows + 1;
+    }
+    if conf.cx < conf.coloff {
+        conf.coloff = conf.cx;
+    }
+    if conf.cx >= conf.coloff + conf.screencols {
+        conf.coloff = conf.cx - conf.screencols + 1;
     }
-    true
 }
 
 /** output **/
-fn draw_rows(conf: &EditorConfig) {
-    let mut buf = String::new();
-    buf += "\x1b[?25l";             // Hide cursor
-    buf += "\x1b[H";                // Move cursor to top-right
-    for y in 0..conf.rows {
-        if y == conf.rows / 3 {
-            let welcome = format!("Welcome to Fanto editor version {}", VERSION);
-            let len = std::cmp::min(conf.cols as usize, welcome.len());
-            let padding = (conf.cols as usize - len) / 2;
-            if padding > 0 {
-                buf += "~";
-                buf += &std::iter::repeat(" ").take(padding - 1).collect::<String>();
+fn draw_rows(conf: &EditorConfig) -> Result<()> {
+    let mut out: Vec<u8> = Vec::new();
+    out.hide(&conf.ti)?;
+    out.goto(&conf.ti, 1, 1)?;
+    for y in 0..conf.screenrows {
+        let filerow = (y + conf.rowoff) as usize;
+        if filerow >= conf.rows.len() {
+            if conf.rows.is_empty() && y == conf.screenrows / 3 {
+                let welcome = format!("Welcome to Fanto editor version {}", VERSION);
+                let len = std::cmp::min(conf.screencols as usize, welcome.len());
+                let padding = (conf.screencols as usize - len) / 2;
+                if padding > 0 {
+                    out.write(b"~")?;
+                    out.write(std::iter::repeat(b' ').take(padding - 1).collect::<Vec<u8>>().as_slice())?;
+                }
+                out.write(welcome.split_at(len - 1).0.as_bytes())?;
+            } else {
+                out.write(b"~")?;
             }
-            buf += welcome.split_at(len - 1).0;
         } else {
-            buf += "~";
+            let render = &conf.rows[filerow].render;
+            let start = std::cmp::min(conf.coloff as usize, render.len());
+            let end = std::cmp::min(start + conf.screencols as usize, render.len());
+            draw_row_span(&mut out, conf, filerow, start, end)?;
         }
-        buf += "\x1b[K";
-        if y < conf.rows - 1 {
-            buf += "\r\n";
+        out.clr_eol(&conf.ti)?;
+        out.write(b"\r\n")?;
+    }
+
+    let msg_len = std::cmp::min(conf.status_msg.len(), conf.screencols as usize);
+    out.write(conf.status_msg[..msg_len].as_bytes())?;
+    out.clr_eol(&conf.ti)?;
+
+    out.goto(&conf.ti, conf.cx - conf.coloff + 1, conf.cy - conf.rowoff + 1)?;
+    out.show(&conf.ti)?;
+
+    std::io::stdout().write_all(&out).chain_err(|| "Unable to write to stdout")?;
+    std::io::stdout().flush().chain_err(|| "Unable to flush stdout")?;
+    Ok(())
+}
+
+/// Write `render[start..end]` of buffer row `filerow`, inverting the SGR
+/// attributes over the portion that overlaps `conf.hl` (e.g. a search
+/// match), if any.
+fn draw_row_span(out: &mut Vec<u8>, conf: &EditorConfig, filerow: usize, start: usize, end: usize) -> Result<()> {
+    let render = &conf.rows[filerow].render;
+    match conf.hl {
+        Some((row, hl_start, hl_end)) if row == filerow && hl_start < end && hl_end > start => {
+            let clip_start = std::cmp::max(hl_start, start);
+            let clip_end = std::cmp::min(hl_end, end);
+            out.write(render[start..clip_start].as_bytes())?;
+            out.invert()?;
+            out.write(render[clip_start..clip_end].as_bytes())?;
+            out.reset_style()?;
+            out.write(render[clip_end..end].as_bytes())?;
+        }
+        _ => {
+            out.write(render[start..end].as_bytes())?;
         }
     }
-    buf += &format!("\x1b[{};{}H", conf.cy + 1, conf.cx + 1);
-    buf += "\x1b[?25h";             // Show cursor
-    print!("{}", buf);
-    let _ = std::io::stdout().flush();
+    Ok(())
+}
+
+fn refresh_screen(conf: &EditorConfig) -> Result<()> {
+    let mut out = std::io::stdout();
+    out.clear(&conf.ti)?;
+    out.flush().chain_err(|| "Unable to flush stdout")?;
+    Ok(())
 }
 
-fn refresh_screen() {
-    print!("\x1b[2J\x1b[H");
-    let _ = std::io::stdout().flush();
+/// Prompt on the message bar with `fmt` (its one `{}` is replaced by the
+/// input typed so far), redrawing and invoking `callback(conf, query,
+/// key)` after every keystroke. Returns the final query, or `None` if the
+/// user cancelled with Escape.
+fn prompt<I, F>(conf: &mut EditorConfig, bytes: &mut I, fmt: &str, mut callback: F) -> Result<Option<String>>
+    where I: Iterator<Item = std::io::Result<u8>>,
+          F: FnMut(&mut EditorConfig, &str, Input)
+{
+    let mut query = String::new();
+    loop {
+        conf.status_msg = fmt.replacen("{}", &query, 1);
+        draw_rows(conf)?;
+
+        match read_key(bytes) {
+            Some(key @ Input::Control(b)) if b == ctrl('h') || b == 127 => {
+                query.pop();
+                callback(conf, &query, key);
+            }
+            Some(key @ Input::Control(b)) if b == ESCAPE as u8 => {
+                conf.status_msg.clear();
+                callback(conf, &query, key);
+                return Ok(None);
+            }
+            Some(key @ Input::Control(b)) if b == ctrl('m') => {
+                conf.status_msg.clear();
+                callback(conf, &query, key);
+                return Ok(Some(query));
+            }
+            Some(key @ Input::Char(c)) => {
+                query.push(c);
+                callback(conf, &query, key);
+            }
+            Some(key) => callback(conf, &query, key),
+            None => return Ok(None),
+        }
+    }
+}
+
+/// Incremental search: scan from the current match for `query` on every
+/// keystroke, moving the cursor to the hit. Arrow keys step to the
+/// next/previous occurrence (wrapping around the buffer); Escape restores
+/// the cursor/scroll position saved before the search started, Enter
+/// keeps it.
+fn search<I>(conf: &mut EditorConfig, bytes: &mut I) -> Result<()>
+    where I: Iterator<Item = std::io::Result<u8>>
+{
+    let saved_cx = conf.cx;
+    let saved_cy = conf.cy;
+    let saved_rowoff = conf.rowoff;
+    let saved_coloff = conf.coloff;
+
+    let mut last_match: Option<usize> = None;
+    let mut direction: i32 = 1;
+
+    let found = prompt(conf, bytes, "Search: {} (use ESC/Enter/Arrows)", |conf, query, key| {
+        // Arrow keys step past the current match; any other key re-checks
+        // the current match's row first, so the match stays put while it
+        // still fits the query.
+        let mut step = match key {
+            Input::ArrowRight | Input::ArrowDown => { direction = 1; 1 }
+            Input::ArrowLeft | Input::ArrowUp => { direction = -1; -1 }
+            _ => { direction = 1; 0 }
+        };
+
+        conf.hl = None;
+        if query.is_empty() || conf.rows.is_empty() {
+            return;
+        }
+
+        let nrows = conf.rows.len();
+        let mut current = last_match.unwrap_or(saved_cy as usize);
+        for _ in 0..nrows {
+            current = (current as i32 + step).rem_euclid(nrows as i32) as usize;
+            step = direction;
+            if let Some(col) = conf.rows[current].render.find(query) {
+                last_match = Some(current);
+                conf.cy = current as u16;
+                conf.cx = conf.rows[current].render[..col].chars().count() as u16;
+                conf.hl = Some((current, col, col + query.len()));
+                clamp_scroll(conf);
+                break;
+            }
+        }
+    })?;
+
+    if found.is_none() {
+        conf.cx = saved_cx;
+        conf.cy = saved_cy;
+        conf.rowoff = saved_rowoff;
+        conf.coloff = saved_coloff;
+    }
+    conf.hl = None;
+    Ok(())
 }
 
 fn read_key<I>(bytes: &mut I) -> Option<Input>
@@ -167,6 +429,18 @@ fn read_key<I>(bytes: &mut I) -> Option<Input>
                         ('[', 'B') => Some(Input::ArrowDown),
                         ('[', 'C') => Some(Input::ArrowRight),
                         ('[', 'D') => Some(Input::ArrowLeft),
+                        ('[', 'H') => Some(Input::Home),
+                        ('[', 'F') => Some(Input::End),
+                        ('[', d) if d.is_digit(10) => {
+                            match bytes.next() {
+                                Some(Ok(b3)) => match (d, b3 as char) {
+                                    ('5', '~') => Some(Input::PageUp),
+                                    ('6', '~') => Some(Input::PageDown),
+                                    _ => Some(Input::Char(d)),
+                                },
+                                _ => Some(Input::Char(d)),
+                            }
+                        }
                         (c, _) => Some(Input::Char(c))
                     }
                 } else {
@@ -181,29 +455,50 @@ fn read_key<I>(bytes: &mut I) -> Option<Input>
 }
 
 /** main function **/
+#[cfg(feature = "async")]
 fn run() -> Result<()> {
+    async_run::run()
+}
+
+#[cfg(not(feature = "async"))]
+fn run() -> Result<()> {
+    let path = std::env::args().nth(1);
+
+    if !is_interactive() {
+        return match path {
+            Some(path) => dump_file(&path),
+            None => Err("stdin/stdout is not a terminal; pass a file to print, or run interactively".into()),
+        };
+    }
+
     let mut config = term_config().chain_err(|| "Unable to initialize terminal config")?;
+    if let Some(path) = path {
+        config.rows = buffer::open(path)?;
+    }
 
-    let stdin = std::io::stdin();
-    let mut bytes = stdin.lock().bytes();
-    enable_raw_mode()?;
-    draw_rows(&config);
+    let result = (|| -> Result<()> {
+        let stdin = std::io::stdin();
+        let mut bytes = stdin.lock().bytes();
+        enable_raw_mode()?;
+        draw_rows(&config)?;
 
-    while let Some(i) = read_key(&mut bytes) {
-        if !process_key(i, &mut config) {
-            break;
+        while let Some(i) = read_key(&mut bytes) {
+            if !process_key(i, &mut config, &mut bytes)? {
+                break;
+            }
+            draw_rows(&config)?;
         }
-        // draw_rows(&config);
-    }
-    println!();
+        println!();
+        Ok(())
+    })();
 
-    termios::tcsetattr(STDIN_FILENO, termios::TCSAFLUSH, &config.orig)?;
-    Ok(())
+    refresh_screen(&config)?;
+    termios::tcsetattr(STDIN_FILENO, termios::SetArg::TCSAFLUSH, &config.orig)?;
+    result
 }
 
 fn main() {
     let res = run();
-    refresh_screen();
     if let Err(ref e) = res {
         use std::io::Write;
         let stderr = &mut ::std::io::stderr();
@@ -0,0 +1,57 @@
+//! A small, testable output layer: type-checked cursor/screen control
+//! methods for any `Write`, built on the capabilities `terminfo` resolves
+//! for the current terminal. Callers write `out.goto(ti, x, y)` instead of
+//! assembling raw CSI strings by hand.
+
+use std::io::{self, Write};
+
+use crate::terminfo::Terminfo;
+
+pub trait TermControl: Write {
+    /// Write a Control Sequence Introducer (`ESC [`) followed by `bytes`.
+    fn csi(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.write(b"\x1b[")?;
+        self.write(bytes)
+    }
+
+    /// Write an Operating System Command (`ESC ]`) followed by `bytes`.
+    fn osc(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.write(b"\x1b]")?;
+        self.write(bytes)
+    }
+
+    fn clear(&mut self, ti: &Terminfo) -> io::Result<usize> {
+        self.write(ti.get("clear").unwrap_or("\x1b[2J\x1b[H").as_bytes())
+    }
+
+    fn hide(&mut self, ti: &Terminfo) -> io::Result<usize> {
+        self.write(ti.get("cursor_invisible").unwrap_or("\x1b[?25l").as_bytes())
+    }
+
+    fn show(&mut self, ti: &Terminfo) -> io::Result<usize> {
+        self.write(ti.get("cursor_normal").unwrap_or("\x1b[?25h").as_bytes())
+    }
+
+    fn clr_eol(&mut self, ti: &Terminfo) -> io::Result<usize> {
+        self.write(ti.get("clr_eol").unwrap_or("\x1b[K").as_bytes())
+    }
+
+    /// Move the cursor to the 1-based screen column `x`, row `y`.
+    fn goto(&mut self, ti: &Terminfo, x: u16, y: u16) -> io::Result<usize> {
+        let seq = ti.apply("cursor_address", &[y as i32 - 1, x as i32 - 1])
+            .unwrap_or_else(|_| format!("\x1b[{};{}H", y, x));
+        self.write(seq.as_bytes())
+    }
+
+    fn reset_style(&mut self) -> io::Result<usize> {
+        self.csi(b"0m")
+    }
+
+    /// Invert the foreground/background (SGR 7) until the next
+    /// `reset_style`, used to highlight a span such as a search match.
+    fn invert(&mut self) -> io::Result<usize> {
+        self.csi(b"7m")
+    }
+}
+
+impl<W: Write> TermControl for W {}
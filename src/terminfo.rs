@@ -0,0 +1,386 @@
+//! Lookup and parsing of the compiled terminfo database, so the editor can
+//! emit the escape sequences the current `$TERM` actually understands
+//! instead of assuming a fixed VT100-like terminal.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::errors::*;
+
+const MAGIC: i16 = 0o432;
+
+const BOOL_NAMES: &'static [&'static str] = &[
+    "auto_left_margin", "auto_right_margin", "no_esc_ctlc", "ceol_standout_glitch",
+    "eat_newline_glitch", "erase_overstrike", "generic_type", "hard_copy",
+    "has_meta_key", "has_status_line", "insert_null_glitch", "memory_above",
+    "memory_below", "move_insert_mode", "move_standout_mode", "over_strike",
+    "status_line_esc_ok", "dest_tabs_magic_smso", "tilde_glitch",
+    "transparent_underline", "xon_xoff", "needs_xon_xoff", "prtr_silent",
+    "hard_cursor", "non_rev_rmcup", "no_pad_char", "non_dest_scroll_region",
+    "can_change", "back_color_erase", "hue_lightness_saturation",
+    "col_addr_glitch", "cr_cancels_micro_mode", "has_print_wheel",
+    "row_addr_glitch", "semi_auto_right_margin", "cpi_changes_res",
+    "lpi_changes_res",
+];
+
+const NUM_NAMES: &'static [&'static str] = &[
+    "columns", "init_tabs", "lines", "lines_of_memory", "magic_cookie_glitch",
+    "padding_baud_rate", "virtual_terminal", "width_status_line",
+    "num_labels", "label_height", "label_width", "max_attributes",
+    "maximum_windows", "max_colors", "max_pairs", "no_color_video",
+    "buffer_capacity", "dot_vert_spacing", "dot_horz_spacing",
+    "max_micro_address", "max_micro_jump", "micro_col_size", "micro_line_size",
+    "number_of_pins", "output_res_char", "output_res_line",
+    "output_res_horz_inch", "output_res_vert_inch", "print_rate",
+    "wide_char_size", "buttons", "bit_image_entwining", "bit_image_type",
+];
+
+const STR_NAMES: &'static [&'static str] = &[
+    "back_tab", "bell", "carriage_return", "change_scroll_region",
+    "clear_all_tabs", "clear", "clr_eol", "clr_eos", "column_address",
+    "command_character", "cursor_address", "cursor_down", "cursor_home",
+    "cursor_invisible", "cursor_left", "cursor_mem_address", "cursor_normal",
+    "cursor_right", "cursor_to_ll", "cursor_up", "cursor_visible",
+    "delete_character", "delete_line", "dis_status_line", "down_half_line",
+    "enter_alt_charset_mode", "enter_blink_mode", "enter_bold_mode",
+    "enter_ca_mode", "enter_delete_mode", "enter_dim_mode",
+    "enter_insert_mode", "enter_secure_mode", "enter_protected_mode",
+    "enter_reverse_mode", "enter_standout_mode", "enter_underline_mode",
+    "erase_chars", "exit_alt_charset_mode", "exit_attribute_mode",
+    "exit_ca_mode", "exit_delete_mode", "exit_insert_mode",
+    "exit_standout_mode", "exit_underline_mode", "flash_screen", "form_feed",
+    "from_status_line", "init_1string", "init_2string", "init_3string",
+    "init_file", "insert_character", "insert_line", "insert_padding",
+    "key_backspace", "key_catab", "key_clear", "key_ctab", "key_dc", "key_dl",
+    "key_down", "key_eic", "key_eol", "key_eos", "key_f0", "key_f1",
+    "key_f10", "key_f2", "key_f3", "key_f4", "key_f5", "key_f6", "key_f7",
+    "key_f8", "key_f9", "key_home", "key_ic", "key_il", "key_left", "key_ll",
+    "key_npage", "key_ppage", "key_right", "key_sf", "key_sr", "key_stab",
+    "key_up", "keypad_local", "keypad_xmit", "lab_f0", "lab_f1", "lab_f10",
+    "lab_f2", "lab_f3", "lab_f4", "lab_f5", "lab_f6", "lab_f7", "lab_f8",
+    "lab_f9", "meta_off", "meta_on", "newline", "pad_char", "parm_dch",
+    "parm_delete_line", "parm_down_cursor", "parm_ich", "parm_index",
+    "parm_insert_line", "parm_left_cursor", "parm_right_cursor",
+    "parm_rindex", "parm_up_cursor", "pkey_key", "pkey_local", "pkey_xmit",
+    "print_screen", "prtr_off", "prtr_on", "repeat_char", "reset_1string",
+    "reset_2string", "reset_3string", "reset_file", "restore_cursor",
+    "row_address", "save_cursor", "scroll_forward", "scroll_reverse",
+    "set_attributes", "set_tab", "set_window", "tab", "to_status_line",
+    "underline_char", "up_half_line", "init_prog", "key_a1", "key_a3",
+    "key_b2", "key_c1", "key_c3", "prtr_non", "char_padding", "acs_chars",
+    "plab_norm", "key_btab", "enter_xon_mode", "exit_xon_mode",
+    "enter_am_mode", "exit_am_mode", "xon_character", "xoff_character",
+    "ena_acs", "label_on", "label_off",
+];
+
+/// Parsed capabilities for a single terminal type, as found in the legacy
+/// terminfo binary format (`term(5)`).
+pub struct Terminfo {
+    booleans: HashMap<&'static str, bool>,
+    numbers: HashMap<&'static str, i16>,
+    strings: HashMap<&'static str, String>,
+}
+
+impl Terminfo {
+    /// Locate and parse the terminfo entry for `$TERM`.
+    pub fn from_env() -> Result<Terminfo> {
+        let term = env::var("TERM").chain_err(|| "TERM is not set")?;
+        let path = locate(&term)
+            .ok_or_else(|| format!("no terminfo entry found for '{}'", term))?;
+        let data = fs::read(&path)
+            .chain_err(|| format!("unable to read terminfo file {}", path.display()))?;
+        parse(&data)
+    }
+
+    /// A `Terminfo` with no capabilities at all. Every `TermControl` method
+    /// falls back to a hardcoded CSI sequence (or a no-op) when a
+    /// capability is absent, so this is a safe stand-in when the real
+    /// database can't be loaded (e.g. an unsupported terminfo format).
+    pub fn empty() -> Terminfo {
+        Terminfo {
+            booleans: HashMap::new(),
+            numbers: HashMap::new(),
+            strings: HashMap::new(),
+        }
+    }
+
+    pub fn has(&self, cap: &str) -> bool {
+        *self.booleans.get(cap).unwrap_or(&false)
+    }
+
+    pub fn number(&self, cap: &str) -> Option<i16> {
+        self.numbers.get(cap).cloned()
+    }
+
+    pub fn get(&self, cap: &str) -> Option<&str> {
+        self.strings.get(cap).map(String::as_str)
+    }
+
+    /// Run the terminfo parameterized-string mini-language for `cap`,
+    /// substituting `params` (e.g. `%p1%d`, `%i`, `%{n}`, `%+`).
+    pub fn apply(&self, cap: &str, params: &[i32]) -> Result<String> {
+        let template = self.get(cap)
+            .ok_or_else(|| format!("capability '{}' not available", cap))?;
+        eval(template, params)
+    }
+}
+
+/// Search `$TERMINFO`, `~/.terminfo`, and the standard system directories
+/// for the compiled entry `<first-char-or-hex>/<name>`.
+fn locate(term: &str) -> Option<PathBuf> {
+    let first = term.chars().next()?;
+
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    if let Ok(dir) = env::var("TERMINFO") {
+        dirs.push(PathBuf::from(dir));
+    }
+    if let Ok(home) = env::var("HOME") {
+        dirs.push(Path::new(&home).join(".terminfo"));
+    }
+    dirs.push(PathBuf::from("/usr/share/terminfo"));
+    dirs.push(PathBuf::from("/etc/terminfo"));
+    dirs.push(PathBuf::from("/lib/terminfo"));
+
+    for dir in dirs {
+        let by_char = dir.join(first.to_string()).join(term);
+        if by_char.is_file() {
+            return Some(by_char);
+        }
+        let by_hex = dir.join(format!("{:x}", first as u32)).join(term);
+        if by_hex.is_file() {
+            return Some(by_hex);
+        }
+    }
+    None
+}
+
+fn read_i16(data: &[u8], pos: usize) -> Result<i16> {
+    if pos + 2 > data.len() {
+        return Err("truncated terminfo file".into());
+    }
+    Ok(i16::from_le_bytes([data[pos], data[pos + 1]]))
+}
+
+/// Parse a legacy-format terminfo file: a 6 x `i16` header (magic,
+/// names-size, bool-count, num-count, string-offset-count, string-table
+/// size), the names section, the booleans, the numbers, and finally the
+/// string offsets plus the string table they index into.
+fn parse(data: &[u8]) -> Result<Terminfo> {
+    if read_i16(data, 0)? != MAGIC {
+        return Err("not a legacy terminfo file (bad magic number)".into());
+    }
+    let name_size = read_i16(data, 2)? as usize;
+    let bool_count = read_i16(data, 4)? as usize;
+    let num_count = read_i16(data, 6)? as usize;
+    let str_count = read_i16(data, 8)? as usize;
+    let str_size = read_i16(data, 10)? as usize;
+
+    let mut pos = 12 + name_size;
+
+    let mut booleans = HashMap::new();
+    for (i, name) in BOOL_NAMES.iter().enumerate().take(bool_count) {
+        booleans.insert(*name, *data.get(pos + i).ok_or("truncated terminfo booleans")? == 1);
+    }
+    pos += bool_count;
+
+    if pos % 2 == 1 {
+        pos += 1; // the numbers section always starts on an even offset
+    }
+
+    let mut numbers = HashMap::new();
+    for (i, name) in NUM_NAMES.iter().enumerate().take(num_count) {
+        let v = read_i16(data, pos + i * 2)?;
+        if v >= 0 {
+            numbers.insert(*name, v);
+        }
+    }
+    pos += num_count * 2;
+
+    let offsets_start = pos;
+    pos += str_count * 2;
+    let table_start = pos;
+    let table = data.get(table_start..table_start + str_size)
+        .ok_or("truncated terminfo string table")?;
+
+    let mut strings = HashMap::new();
+    for (i, name) in STR_NAMES.iter().enumerate().take(str_count) {
+        let off = read_i16(data, offsets_start + i * 2)?;
+        if off < 0 {
+            continue;
+        }
+        let off = off as usize;
+        let end = table[off..].iter().position(|&b| b == 0)
+            .map(|p| off + p)
+            .unwrap_or(table.len());
+        if let Ok(s) = std::str::from_utf8(&table[off..end]) {
+            strings.insert(*name, s.to_string());
+        }
+    }
+
+    Ok(Terminfo { booleans, numbers, strings })
+}
+
+/// Evaluate the terminfo parameterized-string mini-language: a small
+/// stack machine driven by `%`-escapes (`%pN` pushes a param, `%d`/`%c`
+/// pop and print, `%i` increments the first two params, `%{n}` pushes a
+/// literal, and `%+ %- %* %/ %m` etc. pop two operands and push the
+/// result).
+fn eval(template: &str, params: &[i32]) -> Result<String> {
+    let mut params: Vec<i32> = params.to_vec();
+    let mut stack: Vec<i32> = Vec::new();
+    let mut vars = [0i32; 26];
+    let mut out = String::new();
+    let chars: Vec<char> = template.chars().collect();
+
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] != '%' {
+            out.push(chars[i]);
+            i += 1;
+            continue;
+        }
+        i += 1;
+        let op = *chars.get(i).ok_or("terminfo: dangling '%' in capability string")?;
+        match op {
+            '%' => out.push('%'),
+            'i' => {
+                if let Some(p) = params.get_mut(0) { *p += 1; }
+                if let Some(p) = params.get_mut(1) { *p += 1; }
+            }
+            'd' => out += &pop(&mut stack)?.to_string(),
+            'c' => out.push(pop(&mut stack)? as u8 as char),
+            'p' => {
+                i += 1;
+                let n = chars.get(i).and_then(|c| c.to_digit(10))
+                    .ok_or("terminfo: '%p' must be followed by a digit")? as usize;
+                stack.push(*params.get(n - 1).unwrap_or(&0));
+            }
+            '{' => {
+                i += 1;
+                let start = i;
+                while chars.get(i).map_or(false, |c| c.is_ascii_digit()) {
+                    i += 1;
+                }
+                let n: i32 = chars[start..i].iter().collect::<String>().parse()
+                    .chain_err(|| "terminfo: bad '%{n}' literal")?;
+                stack.push(n);
+            }
+            'g' => {
+                i += 1;
+                stack.push(vars[var_index(chars.get(i))?]);
+            }
+            'P' => {
+                i += 1;
+                vars[var_index(chars.get(i))?] = pop(&mut stack)?;
+            }
+            '+' | '-' | '*' | '/' | 'm' | '&' | '|' | '^' | '=' | '>' | '<' | 'A' | 'O' => {
+                let b = pop(&mut stack)?;
+                let a = pop(&mut stack)?;
+                stack.push(match op {
+                    '+' => a + b,
+                    '-' => a - b,
+                    '*' => a * b,
+                    '/' => if b != 0 { a / b } else { 0 },
+                    'm' => if b != 0 { a % b } else { 0 },
+                    '&' => a & b,
+                    '|' => a | b,
+                    '^' => a ^ b,
+                    '=' => (a == b) as i32,
+                    '>' => (a > b) as i32,
+                    '<' => (a < b) as i32,
+                    'A' => ((a != 0) && (b != 0)) as i32,
+                    'O' => ((a != 0) || (b != 0)) as i32,
+                    _ => unreachable!(),
+                });
+            }
+            '!' | '~' => {
+                let a = pop(&mut stack)?;
+                stack.push(if op == '!' { (a == 0) as i32 } else { !a });
+            }
+            _ => {} // directives we don't need yet (%s, conditionals, ...)
+        }
+        i += 1;
+    }
+    Ok(out)
+}
+
+fn pop(stack: &mut Vec<i32>) -> Result<i32> {
+    stack.pop().ok_or_else(|| "terminfo: stack underflow evaluating capability".into())
+}
+
+fn var_index(c: Option<&char>) -> Result<usize> {
+    match c {
+        Some(&c) if c.is_ascii_lowercase() => Ok(c as usize - 'a' as usize),
+        _ => Err("terminfo: '%g'/'%P' must be followed by a lowercase letter".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_i16(buf: &mut Vec<u8>, v: i16) {
+        buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    #[test]
+    fn parse_reads_booleans_numbers_and_strings() {
+        let name = b"test\0";
+        let table = b"bt\0"; // "back_tab" capability string
+
+        let mut data = Vec::new();
+        push_i16(&mut data, MAGIC);
+        push_i16(&mut data, name.len() as i16);
+        push_i16(&mut data, 1); // bool_count
+        push_i16(&mut data, 1); // num_count
+        push_i16(&mut data, 1); // str_count
+        push_i16(&mut data, table.len() as i16); // str_size
+        data.extend_from_slice(name);
+        data.push(1); // auto_left_margin = true
+        if data.len() % 2 == 1 {
+            data.push(0); // pad to an even offset before the numbers section
+        }
+        push_i16(&mut data, 80); // columns
+        push_i16(&mut data, 0); // back_tab's offset into the string table
+        data.extend_from_slice(table);
+
+        let ti = parse(&data).expect("well-formed synthetic entry should parse");
+        assert!(ti.has("auto_left_margin"));
+        assert!(!ti.has("auto_right_margin"));
+        assert_eq!(ti.number("columns"), Some(80));
+        assert_eq!(ti.get("back_tab"), Some("bt"));
+    }
+
+    #[test]
+    fn parse_rejects_bad_magic() {
+        let data = vec![0u8; 12];
+        assert!(parse(&data).is_err());
+    }
+
+    #[test]
+    fn eval_substitutes_a_param() {
+        assert_eq!(eval("%p1%d", &[42]).unwrap(), "42");
+    }
+
+    #[test]
+    fn eval_increments_the_first_two_params() {
+        assert_eq!(eval("%i%p1%d;%p2%d", &[1, 2]).unwrap(), "2;3");
+    }
+
+    #[test]
+    fn eval_does_arithmetic_on_the_stack() {
+        assert_eq!(eval("%p1%p2%+%d", &[2, 3]).unwrap(), "5");
+    }
+
+    #[test]
+    fn eval_pushes_a_literal() {
+        assert_eq!(eval("%{10}%d", &[]).unwrap(), "10");
+    }
+
+    #[test]
+    fn eval_reports_stack_underflow() {
+        assert!(eval("%d", &[]).is_err());
+    }
+}